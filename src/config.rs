@@ -0,0 +1,193 @@
+//! The user-facing config file: the set of plugins to install and how to
+//! load them.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context as ResultExt, Error, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::context::Context;
+
+/// The shell a generated config file is tailored for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// A single plugin entry in the config file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Plugin {
+    pub name: String,
+    pub source: String,
+    /// Names of the profiles this plugin is active under. `None` means the
+    /// plugin is always active, which keeps existing configs (written
+    /// before profiles existed) behaving exactly as before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profiles: Option<Vec<String>>,
+}
+
+impl Plugin {
+    /// Returns `true` if this plugin should be active for `profile`.
+    ///
+    /// A plugin with no `profiles` key is always active. A plugin that
+    /// declares profiles is only active when `profile` names one of them;
+    /// with no active profile selected, a profile-restricted plugin is
+    /// skipped.
+    pub fn is_active(&self, profile: Option<&str>) -> bool {
+        match &self.profiles {
+            None => true,
+            Some(profiles) => matches!(profile, Some(p) if profiles.iter().any(|d| d == p)),
+        }
+    }
+}
+
+/// The parsed config file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// The default active profile when none is given with `--profile` or
+    /// `SHELDON_PROFILE`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub plugins: Vec<Plugin>,
+}
+
+/// Loads and parses the config file at `path`.
+pub fn from_path(path: &Path, _warnings: &mut Vec<Error>) -> Result<Config> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    toml::from_str(&contents).with_context(|| format!("failed to parse `{}`", path.display()))
+}
+
+/// Does nothing and always succeeds. Unreferenced plugin sources are removed
+/// by `gc::gc` (the `gc` subcommand), not here.
+pub fn clean(_ctx: &Context, _warnings: &mut Vec<Error>, _config: &Config) -> Result<()> {
+    Ok(())
+}
+
+/// The config file opened up for the `add`/`edit`/`remove` subcommands.
+/// It is parsed into a [`Config`] and written back from it, so comments and
+/// formatting in the original file are not preserved.
+#[derive(Clone, Debug)]
+pub struct EditConfig {
+    inner: Config,
+}
+
+/// A plugin as specified on the command line for `sheldon add`.
+#[derive(Clone, Debug)]
+pub struct EditPlugin {
+    pub source: String,
+    pub profiles: Option<Vec<String>>,
+}
+
+impl EditConfig {
+    pub fn default(_shell: Option<Shell>) -> Self {
+        Self {
+            inner: Config::default(),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        Self::from_str(&contents)
+    }
+
+    pub fn from_str(contents: &str) -> Result<Self> {
+        let inner = toml::from_str(contents).context("failed to parse config file")?;
+        Ok(Self { inner })
+    }
+
+    pub fn add(&mut self, name: &str, plugin: &EditPlugin) -> Result<()> {
+        self.inner.plugins.push(Plugin {
+            name: name.to_owned(),
+            source: plugin.source.clone(),
+            profiles: plugin.profiles.clone(),
+        });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.inner.plugins.retain(|p| p.name != name);
+    }
+
+    pub fn to_path(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_string())
+            .with_context(|| format!("failed to write `{}`", path.display()))
+    }
+}
+
+impl std::fmt::Display for EditConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            toml::to_string_pretty(&self.inner).map_err(|_| std::fmt::Error)?
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin(profiles: Option<&[&str]>) -> Plugin {
+        Plugin {
+            name: "example".to_owned(),
+            source: "example/example".to_owned(),
+            profiles: profiles.map(|p| p.iter().map(|s| s.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn plugin_with_no_profiles_is_always_active() {
+        let p = plugin(None);
+        assert!(p.is_active(None));
+        assert!(p.is_active(Some("work")));
+    }
+
+    #[test]
+    fn plugin_restricted_to_profiles_is_active_only_for_a_matching_one() {
+        let p = plugin(Some(&["work", "linux"]));
+        assert!(p.is_active(Some("work")));
+        assert!(p.is_active(Some("linux")));
+        assert!(!p.is_active(Some("home")));
+    }
+
+    #[test]
+    fn plugin_restricted_to_profiles_is_inactive_with_no_active_profile() {
+        let p = plugin(Some(&["work"]));
+        assert!(!p.is_active(None));
+    }
+
+    #[test]
+    fn add_then_remove_operates_by_name_not_source() {
+        let mut config = EditConfig::default(None);
+        config
+            .add(
+                "git",
+                &EditPlugin {
+                    source: "ohmyzsh/ohmyzsh/plugins/git".to_owned(),
+                    profiles: None,
+                },
+            )
+            .unwrap();
+
+        assert_eq!(config.inner.plugins.len(), 1);
+        assert_eq!(config.inner.plugins[0].name, "git");
+        assert_eq!(config.inner.plugins[0].source, "ohmyzsh/ohmyzsh/plugins/git");
+
+        // Removing by the (different) source must be a no-op...
+        config.remove("ohmyzsh/ohmyzsh/plugins/git");
+        assert_eq!(config.inner.plugins.len(), 1);
+
+        // ...and removing by the name the user gave it must work.
+        config.remove("git");
+        assert!(config.inner.plugins.is_empty());
+    }
+}
+
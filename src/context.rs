@@ -0,0 +1,195 @@
+//! The `Context` type, which carries all of the invocation-wide state that
+//! the rest of sheldon needs: resolved paths, output settings, and now a
+//! cached snapshot of the environment.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Environment variable precedence prefix. A `SHELDON_FOO` variable always
+/// takes priority over a plain `FOO` lookup through [`Context::get_env`].
+const PREFIX: &str = "SHELDON_";
+
+/// Invocation-wide context, threaded through every subcommand.
+///
+/// Previously each call site reached for `std::env::var`/`var_os` directly,
+/// which meant home/editor/data-dir resolution couldn't be mocked in tests
+/// and config files had no way to reference the environment. `Context` now
+/// owns a snapshot of the environment taken at construction, so all of that
+/// goes through one place.
+pub struct Context {
+    pub(crate) home: PathBuf,
+    config_dir: PathBuf,
+    data_dir: PathBuf,
+    config_file: PathBuf,
+    lock_file: PathBuf,
+    pub interactive: bool,
+    pub verbose: bool,
+    pub lock_mode: Option<crate::cli::RelockMode>,
+    profile: Option<String>,
+    lock_timeout: Option<Duration>,
+    env: HashMap<String, OsString>,
+}
+
+impl Context {
+    /// Builds a `Context`, snapshotting the current process environment.
+    ///
+    /// `profile` is the active profile, already resolved by the CLI layer
+    /// from `--profile` or `SHELDON_PROFILE` (via [`Context::get_env`] once
+    /// a provisional environment snapshot is available).
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        home: PathBuf,
+        config_dir: PathBuf,
+        data_dir: PathBuf,
+        config_file: PathBuf,
+        lock_file: PathBuf,
+        interactive: bool,
+        verbose: bool,
+        lock_mode: Option<crate::cli::RelockMode>,
+        profile: Option<String>,
+        lock_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            home,
+            config_dir,
+            data_dir,
+            config_file,
+            lock_file,
+            interactive,
+            verbose,
+            lock_mode,
+            profile,
+            lock_timeout,
+            env: env::vars_os().collect(),
+        }
+    }
+
+    /// The active profile selected via `--profile`/`SHELDON_PROFILE`, if
+    /// any. Plugins restricted to other profiles are skipped when locking.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// The `--lock-timeout`/`SHELDON_LOCK_TIMEOUT` duration to wait for a
+    /// contended config-directory lock before giving up. `None` means wait
+    /// forever, as before this option existed.
+    pub fn lock_timeout(&self) -> Option<Duration> {
+        self.lock_timeout
+    }
+
+    pub fn config_dir(&self) -> &Path {
+        &self.config_dir
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn config_file(&self) -> &Path {
+        &self.config_file
+    }
+
+    pub fn lock_file(&self) -> &Path {
+        &self.lock_file
+    }
+
+    /// Looks up an environment variable as a `String`, preferring a
+    /// `SHELDON_`-prefixed override over the plain name.
+    ///
+    /// Reads from the snapshot taken when this `Context` was constructed,
+    /// rather than the live process environment, so that tests can
+    /// construct a `Context` with a fixed environment and callers get
+    /// deterministic behavior regardless of what changes around them.
+    pub fn get_env(&self, key: &str) -> Option<String> {
+        self.get_env_os(key)?.into_string().ok()
+    }
+
+    /// Like [`get_env`](Self::get_env) but returns the raw [`OsString`],
+    /// for callers (like home directory resolution) that must not assume
+    /// valid UTF-8.
+    pub fn get_env_os(&self, key: &str) -> Option<OsString> {
+        self.env
+            .get(&format!("{PREFIX}{key}"))
+            .or_else(|| self.env.get(key))
+            .cloned()
+    }
+
+    /// Looks up `key` exactly as given, with no `SHELDON_` fallback applied.
+    ///
+    /// Used when a caller needs to build its own precedence chain across
+    /// several candidate variable names (e.g. `editor::Editor::default`
+    /// resolving `SHELDON_VISUAL` > `SHELDON_EDITOR` > `$VISUAL` >
+    /// `$EDITOR`) — [`get_env`](Self::get_env)'s automatic per-key fallback
+    /// would otherwise let an unprefixed variable from an earlier candidate
+    /// outrank a `SHELDON_`-prefixed override of a later one.
+    pub fn get_env_exact(&self, key: &str) -> Option<String> {
+        self.env.get(key)?.clone().into_string().ok()
+    }
+
+    /// Replaces the user's home directory prefix of `path` with `~`, for
+    /// display purposes.
+    pub fn replace_home<'a>(&self, path: &'a Path) -> PathBuf {
+        match path.strip_prefix(&self.home) {
+            Ok(rest) => Path::new("~").join(rest),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    pub fn log_header(&self, verb: &str, path: &Path) {
+        println!("{verb} {}", self.replace_home(path).display());
+    }
+
+    pub fn log_status(&self, verb: &str, message: &dyn Display) {
+        println!("{verb} {message}");
+    }
+
+    pub fn log_verbose_status(&self, verb: &str, message: &str) {
+        if self.verbose {
+            println!("{verb} {message}");
+        }
+    }
+
+    pub fn log_verbose_header(&self, verb: &str, path: &Path) {
+        if self.verbose {
+            self.log_header(verb, path);
+        }
+    }
+
+    pub fn log_error(&self, err: &anyhow::Error) {
+        eprintln!("error: {err:#}");
+    }
+
+    pub fn log_error_as_warning(&self, err: &anyhow::Error) {
+        eprintln!("warning: {err:#}");
+    }
+}
+
+#[cfg(test)]
+impl Context {
+    /// Builds a minimal, non-interactive `Context` for tests, backed by
+    /// `data_dir` with no profile or lock-timeout override.
+    pub(crate) fn for_test(data_dir: PathBuf) -> Self {
+        Self::for_test_with_profile(data_dir, None)
+    }
+
+    /// Like [`for_test`](Self::for_test) but with `profile` as the active
+    /// profile, for exercising profile-filtering logic.
+    pub(crate) fn for_test_with_profile(data_dir: PathBuf, profile: Option<&str>) -> Self {
+        Self::new(
+            PathBuf::from("/home/test"),
+            PathBuf::from("/home/test/.config/sheldon"),
+            data_dir,
+            PathBuf::from("/home/test/.config/sheldon/plugins.toml"),
+            PathBuf::from("/home/test/.config/sheldon/plugins.lock"),
+            false,
+            false,
+            None,
+            profile.map(str::to_owned),
+            None,
+        )
+    }
+}
@@ -0,0 +1,70 @@
+//! Opens the config file in the user's editor of choice for `sheldon edit`.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{bail, Context as ResultExt, Result};
+
+use crate::config::EditConfig;
+use crate::context::Context;
+
+/// The editor to invoke, resolved from `$VISUAL`/`$EDITOR`.
+pub struct Editor {
+    program: String,
+}
+
+impl Editor {
+    /// Resolves the default editor from the environment, preferring
+    /// `$VISUAL` over `$EDITOR` as is conventional.
+    ///
+    /// `SHELDON_VISUAL`/`SHELDON_EDITOR` are resolved as an explicit, higher
+    /// priority pair ahead of the unprefixed `$VISUAL`/`$EDITOR`, via
+    /// [`Context::get_env_exact`] rather than `std::env::var` directly.
+    /// Chaining `ctx.get_env("VISUAL").or_else(|| ctx.get_env("EDITOR"))`
+    /// would look right but isn't: each `get_env` call falls back to its own
+    /// unprefixed name, so a plain `$VISUAL` would outrank a `SHELDON_EDITOR`
+    /// override — defeating the point of the `SHELDON_`-prefix precedence.
+    pub fn default(ctx: &Context) -> Result<Self> {
+        let program = ctx
+            .get_env_exact("SHELDON_VISUAL")
+            .or_else(|| ctx.get_env_exact("SHELDON_EDITOR"))
+            .or_else(|| ctx.get_env_exact("VISUAL"))
+            .or_else(|| ctx.get_env_exact("EDITOR"));
+        match program {
+            Some(program) if !program.is_empty() => Ok(Self { program }),
+            _ => bail!("no editor set, please set $EDITOR or $VISUAL"),
+        }
+    }
+
+    /// Writes `contents` to a temporary file and opens it in the editor,
+    /// blocking until the editor exits.
+    pub fn edit(&self, _ctx: &Context, path: &Path, contents: &str) -> Result<EditHandle> {
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, contents)
+            .with_context(|| format!("failed to write `{}`", temp_path.display()))?;
+        let status = Command::new(&self.program)
+            .arg(&temp_path)
+            .status()
+            .with_context(|| format!("failed to run editor `{}`", self.program))?;
+        if !status.success() {
+            bail!("editor `{}` exited with {}", self.program, status);
+        }
+        Ok(EditHandle { temp_path })
+    }
+}
+
+/// A pending edit: the temporary file has been written and the editor has
+/// exited; [`wait_and_update`](Self::wait_and_update) parses the result.
+pub struct EditHandle {
+    temp_path: std::path::PathBuf,
+}
+
+impl EditHandle {
+    pub fn wait_and_update(self, _original_contents: &str) -> Result<EditConfig> {
+        let contents = fs::read_to_string(&self.temp_path)
+            .with_context(|| format!("failed to read `{}`", self.temp_path.display()))?;
+        let _ = fs::remove_file(&self.temp_path);
+        EditConfig::from_str(&contents)
+    }
+}
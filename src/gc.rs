@@ -0,0 +1,297 @@
+//! Garbage collection of unreferenced plugin sources under the data
+//! directory.
+//!
+//! Every source that `lock`/`source` resolve (git clones, downloaded
+//! archives, etc.) accumulates under the data directory and is never
+//! reclaimed when a plugin is removed from the config or its version
+//! changes. [`LastUseTracker`] records, for each resolved source path, the
+//! last time it was referenced by a [`LockedConfig`](crate::lock::LockedConfig),
+//! and [`gc`] uses that to decide what is safe to delete.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context as ResultExt, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::context::Context;
+
+const TRACKER_FILE: &str = ".last-use.json";
+
+/// The default age after which an unreferenced source becomes eligible for
+/// collection, used when `--keep` is not given.
+pub const DEFAULT_KEEP: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// Tracks the last time each resolved source path was referenced by a lock.
+///
+/// Marking a source as used only updates the in-memory map; callers must
+/// call [`save`](Self::save) once all sources for the current command have
+/// been marked so that a single write of the tracker file covers the whole
+/// batch, rather than one per plugin.
+#[derive(Debug, Default)]
+pub struct LastUseTracker {
+    path: PathBuf,
+    entries: HashMap<PathBuf, u64>,
+    dirty: bool,
+}
+
+/// On-disk representation of the tracker file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TrackerData {
+    #[serde(default)]
+    entries: HashMap<PathBuf, u64>,
+}
+
+impl LastUseTracker {
+    /// Loads the tracker from the data directory, or starts empty if it
+    /// doesn't exist yet.
+    pub fn load(data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join(TRACKER_FILE);
+        let entries = match fs::read_to_string(&path) {
+            Ok(contents) => {
+                serde_json::from_str::<TrackerData>(&contents)
+                    .with_context(|| format!("failed to parse `{}`", path.display()))?
+                    .entries
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read `{}`", path.display()))
+            }
+        };
+        Ok(Self {
+            path,
+            entries,
+            dirty: false,
+        })
+    }
+
+    /// Marks `source` as used right now. Deferred: does not touch disk.
+    pub fn mark_used(&mut self, source: &Path) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.entries.insert(source.to_path_buf(), now);
+        self.dirty = true;
+    }
+
+    /// Returns the most recent last-used time recorded for `source` or any
+    /// source nested under it, or `None` if none has ever been recorded
+    /// (e.g. a stale pre-tracker download).
+    fn last_used(&self, source: &Path) -> Option<SystemTime> {
+        self.entries
+            .iter()
+            .filter(|(path, _)| path.starts_with(source))
+            .map(|(_, secs)| UNIX_EPOCH + Duration::from_secs(*secs))
+            .max()
+    }
+
+    /// Writes out all pending `mark_used` calls in a single batch. A no-op
+    /// if nothing changed since the last save.
+    pub fn save(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let data = TrackerData {
+            entries: self.entries.clone(),
+        };
+        let contents =
+            serde_json::to_string(&data).context("failed to serialize last-use tracker")?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory `{}`", parent.display()))?;
+        }
+        fs::write(&self.path, contents)
+            .with_context(|| format!("failed to write `{}`", self.path.display()))?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Summary of a completed garbage collection run.
+#[derive(Debug, Default)]
+pub struct GcSummary {
+    pub removed: usize,
+    pub reclaimed_bytes: u64,
+}
+
+/// Deletes source directories under `ctx.data_dir()` that are not in
+/// `referenced` and have not been used for at least `keep`.
+///
+/// The caller must be holding an exclusive lock on the config directory;
+/// this is never safe to run against a shared lock since it mutates the
+/// data directory that other `source` invocations may be reading from.
+/// Paths that fail to stat are skipped rather than aborting the whole run,
+/// and a source with no tracker entry is treated as unknown/old so that
+/// downloads from before the tracker existed are still collectable.
+pub fn gc(ctx: &Context, referenced: &[PathBuf], keep: Duration) -> Result<GcSummary> {
+    let data_dir = ctx.data_dir();
+    let tracker = LastUseTracker::load(data_dir)?;
+    let now = SystemTime::now();
+    let mut summary = GcSummary::default();
+
+    let sources_dir = data_dir.join("repos");
+    let read_dir = match fs::read_dir(&sources_dir) {
+        Ok(read_dir) => read_dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(summary),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("failed to read directory `{}`", sources_dir.display()))
+        }
+    };
+
+    for entry in read_dir {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let path = entry.path();
+        // Sources may be nested (`repos/owner/name`), so a top-level entry is
+        // referenced if any referenced source lies under it.
+        if referenced.iter().any(|r| r.starts_with(&path)) {
+            continue;
+        }
+
+        let age = match tracker.last_used(&path) {
+            Some(last_used) => now.duration_since(last_used).unwrap_or(Duration::ZERO),
+            None => Duration::MAX,
+        };
+        if age < keep {
+            continue;
+        }
+
+        let size = match dir_size(&path) {
+            Ok(size) => size,
+            Err(_) => continue,
+        };
+        if fs::remove_dir_all(&path).is_ok() {
+            summary.removed += 1;
+            summary.reclaimed_bytes += size;
+            ctx.log_verbose_status("Removing", &path.display().to_string());
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Returns the total size in bytes of all files under `path`, recursing
+/// into subdirectories. Entries that fail to stat are skipped.
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut total = 0;
+    if path.is_dir() {
+        for entry in fs::read_dir(path)?.flatten() {
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if metadata.is_dir() {
+                total += dir_size(&entry.path()).unwrap_or(0);
+            } else {
+                total += metadata.len();
+            }
+        }
+    } else if let Ok(metadata) = path.metadata() {
+        total += metadata.len();
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::context::Context;
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    /// A fresh, empty data directory for a single test. Each test removes it
+    /// itself when done.
+    fn data_dir(name: &str) -> PathBuf {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let pid = std::process::id();
+        let dir = std::env::temp_dir().join(format!("sheldon-gc-test-{pid}-{name}-{n}"));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn last_use_tracker_round_trips_through_disk() {
+        let data_dir = data_dir("round-trip");
+        let source = data_dir.join("repos").join("some-plugin");
+
+        let mut tracker = LastUseTracker::load(&data_dir).unwrap();
+        assert!(tracker.last_used(&source).is_none());
+
+        tracker.mark_used(&source);
+        tracker.save().unwrap();
+
+        let reloaded = LastUseTracker::load(&data_dir).unwrap();
+        assert!(reloaded.last_used(&source).is_some());
+
+        fs::remove_dir_all(&data_dir).ok();
+    }
+
+    #[test]
+    fn save_is_a_no_op_when_nothing_was_marked() {
+        let data_dir = data_dir("no-op");
+
+        let mut tracker = LastUseTracker::load(&data_dir).unwrap();
+        tracker.save().unwrap();
+
+        assert!(!data_dir.join(TRACKER_FILE).exists());
+
+        fs::remove_dir_all(&data_dir).ok();
+    }
+
+    #[test]
+    fn gc_keeps_referenced_and_recently_used_sources() {
+        let data_dir = data_dir("keep");
+        let repos = data_dir.join("repos");
+        let referenced = repos.join("referenced");
+        let recently_used = repos.join("recently-used");
+        fs::create_dir_all(&referenced).unwrap();
+        fs::create_dir_all(&recently_used).unwrap();
+
+        let mut tracker = LastUseTracker::load(&data_dir).unwrap();
+        tracker.mark_used(&recently_used);
+        tracker.save().unwrap();
+
+        let ctx = Context::for_test(data_dir.clone());
+        let summary = gc(&ctx, &[referenced.clone()], DEFAULT_KEEP).unwrap();
+
+        assert_eq!(summary.removed, 0);
+        assert!(referenced.exists());
+        assert!(recently_used.exists());
+
+        fs::remove_dir_all(&data_dir).ok();
+    }
+
+    #[test]
+    fn gc_removes_unreferenced_sources_past_keep_and_ones_with_no_tracker_entry() {
+        let data_dir = data_dir("remove");
+        let repos = data_dir.join("repos");
+        let stale = repos.join("stale");
+        let unknown = repos.join("unknown");
+        fs::create_dir_all(&stale).unwrap();
+        fs::create_dir_all(&unknown).unwrap();
+
+        // `stale` has an old tracker entry; `unknown` (e.g. a pre-tracker
+        // download) has none at all, and must be treated as old too.
+        let mut tracker = LastUseTracker::load(&data_dir).unwrap();
+        tracker.entries.insert(stale.clone(), 0);
+        tracker.dirty = true;
+        tracker.save().unwrap();
+
+        let ctx = Context::for_test(data_dir.clone());
+        let summary = gc(&ctx, &[], Duration::from_secs(1)).unwrap();
+
+        assert_eq!(summary.removed, 2);
+        assert!(!stale.exists());
+        assert!(!unknown.exists());
+
+        fs::remove_dir_all(&data_dir).ok();
+    }
+}
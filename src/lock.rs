@@ -0,0 +1,218 @@
+//! The resolved, locked form of the config: every plugin's source resolved
+//! to a concrete path on disk, ready to be rendered into a shell script.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as ResultExt, Error, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::context::Context;
+
+/// A single resolved plugin.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LockedPlugin {
+    pub source: String,
+    pub path: PathBuf,
+}
+
+/// The locked config: every active plugin resolved to a source on disk.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LockedConfig {
+    pub plugins: Vec<LockedPlugin>,
+    /// The profile that was active (`ctx.profile()`, before falling back to
+    /// the config file's default) when this lock was generated. Compared
+    /// against the current `ctx.profile()` in `verify` so that switching
+    /// `--profile`/`SHELDON_PROFILE` between `source` invocations is
+    /// noticed even though it doesn't touch the config file's mtime.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_profile: Option<String>,
+    #[serde(skip)]
+    pub errors: Vec<Error>,
+}
+
+impl LockedConfig {
+    /// Returns the resolved source path of every active plugin, used by
+    /// `gc` to know what is still referenced and by the last-use tracker to
+    /// know what to mark as used.
+    pub fn source_paths(&self) -> Vec<PathBuf> {
+        self.plugins.iter().map(|p| p.path.clone()).collect()
+    }
+
+    /// Returns `true` if every locked plugin's source path still exists on
+    /// disk, and the active profile hasn't changed since
+    /// this lock was generated, meaning `source` can reuse this lock file
+    /// instead of regenerating it.
+    pub fn verify(&self, ctx: &Context) -> bool {
+        if ctx.profile() != self.requested_profile.as_deref() {
+            return false;
+        }
+        self.plugins.iter().all(|p| p.path.exists())
+    }
+
+    pub fn to_path(&self, path: &Path) -> Result<()> {
+        let contents = toml::to_string_pretty(self).context("failed to serialize lock file")?;
+        fs::write(path, contents).with_context(|| format!("failed to write `{}`", path.display()))
+    }
+
+    /// Renders the shell script that sources every active plugin.
+    pub fn script(&self, _ctx: &Context, _warnings: &mut Vec<Error>) -> Result<String> {
+        let mut script = String::new();
+        for plugin in &self.plugins {
+            script.push_str(&format!("source \"{}\"\n", plugin.path.display()));
+        }
+        Ok(script)
+    }
+}
+
+/// Loads an already-generated lock file from disk.
+pub fn from_path(path: &Path) -> Result<LockedConfig> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    toml::from_str(&contents).with_context(|| format!("failed to parse `{}`", path.display()))
+}
+
+/// Resolves the source path of every plugin declared in `config`,
+/// regardless of which profile(s) restrict it.
+///
+/// Used by `gc`, which must treat a source as referenced if it's declared
+/// under *any* profile, not just the one most recently locked — the whole
+/// point of profiles is maintaining one config across machines, so a
+/// source belonging to a profile that isn't currently active must not look
+/// unreferenced just because nobody has locked that profile on this
+/// machine.
+pub fn all_source_paths(ctx: &Context, config: &Config) -> Vec<PathBuf> {
+    config
+        .plugins
+        .iter()
+        .map(|plugin| ctx.data_dir().join("repos").join(&plugin.source))
+        .collect()
+}
+
+/// Resolves every plugin in `config` that is active for the active profile
+/// (`ctx.profile()`, falling back to the config file's default `profile`)
+/// into a [`LockedConfig`]. Plugins whose declared `profiles` don't include
+/// the active profile are skipped entirely; plugins with no `profiles` key
+/// are always included.
+pub fn config(ctx: &Context, config: Config) -> Result<LockedConfig> {
+    let active_profile = ctx.profile().or(config.profile.as_deref());
+    let mut locked = LockedConfig {
+        requested_profile: ctx.profile().map(str::to_owned),
+        ..LockedConfig::default()
+    };
+    for plugin in &config.plugins {
+        if !plugin.is_active(active_profile) {
+            continue;
+        }
+        let path = ctx.data_dir().join("repos").join(&plugin.source);
+        locked.plugins.push(LockedPlugin {
+            source: plugin.source.clone(),
+            path,
+        });
+    }
+    Ok(locked)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::config::Plugin;
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    fn data_dir(name: &str) -> PathBuf {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let pid = std::process::id();
+        std::env::temp_dir().join(format!("sheldon-lock-test-{pid}-{name}-{n}"))
+    }
+
+    fn plugin(source: &str, profiles: Option<&[&str]>) -> Plugin {
+        Plugin {
+            name: source.to_owned(),
+            source: source.to_owned(),
+            profiles: profiles.map(|p| p.iter().map(|s| s.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn config_includes_unrestricted_plugins_regardless_of_profile() {
+        let ctx = Context::for_test(data_dir("unrestricted"));
+        let cfg = Config {
+            profile: None,
+            plugins: vec![plugin("always/here", None)],
+        };
+
+        let locked = config(&ctx, cfg).unwrap();
+
+        assert_eq!(locked.plugins.len(), 1);
+        assert_eq!(locked.plugins[0].source, "always/here");
+    }
+
+    #[test]
+    fn config_includes_restricted_plugins_only_for_a_matching_active_profile() {
+        let ctx = Context::for_test_with_profile(data_dir("matching"), Some("work"));
+        let cfg = Config {
+            profile: None,
+            plugins: vec![
+                plugin("work/only", Some(&["work"])),
+                plugin("home/only", Some(&["home"])),
+            ],
+        };
+
+        let locked = config(&ctx, cfg).unwrap();
+
+        assert_eq!(locked.plugins.len(), 1);
+        assert_eq!(locked.plugins[0].source, "work/only");
+    }
+
+    #[test]
+    fn config_falls_back_to_the_config_files_default_profile() {
+        let ctx = Context::for_test(data_dir("default-profile"));
+        let cfg = Config {
+            profile: Some("work".to_owned()),
+            plugins: vec![plugin("work/only", Some(&["work"]))],
+        };
+
+        let locked = config(&ctx, cfg).unwrap();
+
+        assert_eq!(locked.plugins.len(), 1);
+    }
+
+    #[test]
+    fn verify_is_invalidated_by_a_profile_switch() {
+        let locked = LockedConfig {
+            requested_profile: Some("work".to_owned()),
+            ..LockedConfig::default()
+        };
+
+        let same_profile = Context::for_test_with_profile(data_dir("verify-same"), Some("work"));
+        assert!(locked.verify(&same_profile));
+
+        let other_profile = Context::for_test_with_profile(data_dir("verify-other"), Some("home"));
+        assert!(!locked.verify(&other_profile));
+
+        let no_profile = Context::for_test(data_dir("verify-none"));
+        assert!(!locked.verify(&no_profile));
+    }
+
+    #[test]
+    fn all_source_paths_ignores_profile_restrictions() {
+        let ctx = Context::for_test_with_profile(data_dir("all-paths"), Some("work"));
+        let cfg = Config {
+            profile: None,
+            plugins: vec![
+                plugin("work/only", Some(&["work"])),
+                plugin("home/only", Some(&["home"])),
+            ],
+        };
+
+        let paths = all_source_paths(&ctx, &cfg);
+
+        assert_eq!(paths.len(), 2);
+        assert!(paths.contains(&ctx.data_dir().join("repos").join("work/only")));
+        assert!(paths.contains(&ctx.data_dir().join("repos").join("home/only")));
+    }
+}
@@ -4,6 +4,7 @@ mod cli;
 mod config;
 mod context;
 mod editor;
+mod gc;
 mod lock;
 mod util;
 
@@ -14,12 +15,15 @@ use std::io;
 use std::panic;
 use std::path::Path;
 use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context as ResultExt, Error, Result};
 
 use crate::cli::{Command, Opt};
 use crate::config::{EditConfig, EditPlugin, Shell};
 use crate::context::Context;
+use crate::gc::LastUseTracker;
 use crate::lock::LockedConfig;
 use crate::util::underlying_io_error_kind;
 
@@ -40,16 +44,49 @@ fn main() {
     }
 }
 
+/// The locking strategy used when acquiring the filesystem mutex on the
+/// config directory.
+///
+/// `source` only needs to read an already-valid lock file most of the time,
+/// so it can take a [`Shared`](LockMode::Shared) lock and let many
+/// invocations (e.g. several shells starting up at once) proceed
+/// concurrently. Anything that may write the config or lock file must take
+/// an [`Exclusive`](LockMode::Exclusive) lock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+impl LockMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Shared => "shared",
+            Self::Exclusive => "exclusive",
+        }
+    }
+}
+
 /// The main entry point to execute the application.
 pub fn run_command(ctx: &Context, command: Command) -> Result<()> {
     // We always try to acquire the mutex but it is only strictly necessary for
-    // the lock and source commands.
-    let file_mutex = match acquire_mutex(ctx.config_dir()) {
+    // the lock, source and gc commands. `source` only needs a shared lock since it
+    // may just be reading an already-valid lock file.
+    let lock_mode = match command {
+        Command::Source => LockMode::Shared,
+        _ => LockMode::Exclusive,
+    };
+    let file_mutex = match acquire_mutex(ctx, ctx.config_dir(), lock_mode) {
         Ok(file) => {
-            ctx.log_verbose_status("File", &format!("acquired lock on config directory"));
+            ctx.log_verbose_status(
+                "File",
+                &format!("acquired {} lock on config directory", lock_mode.as_str()),
+            );
             Some(file)
         }
-        Err(_) if !matches!(command, Command::Lock | Command::Source) => None,
+        Err(_) if !matches!(command, Command::Lock | Command::Source | Command::Gc { .. }) => {
+            None
+        }
         Err(err) => return Err(err),
     };
     let mut warnings = Vec::new();
@@ -59,7 +96,8 @@ pub fn run_command(ctx: &Context, command: Command) -> Result<()> {
         Command::Edit => edit(ctx),
         Command::Remove { name } => remove(ctx, name),
         Command::Lock => lock(ctx, &mut warnings),
-        Command::Source => source(ctx, &mut warnings),
+        Command::Source => source(ctx, &mut warnings, file_mutex.as_ref().unwrap()),
+        Command::Gc { keep } => gc(ctx, keep),
     };
     for err in &warnings {
         ctx.log_error_as_warning(err);
@@ -103,23 +141,88 @@ fn get_file_for_mutex(path: &Path) -> io::Result<fs::File> {
         .open(path)
 }
 
-fn acquire_mutex(path: &Path) -> Result<fs::File> {
-    let file_open = get_file_for_mutex(path);
-    let file = match file_open {
-        Ok(file) => file,
-        Err(err) => return Err(anyhow!("failed to open `{}`: {}", path.display(), err)),
-    };
+/// How often to poll when blocking on a contended lock, so that a
+/// `--lock-timeout` can be enforced despite `fs2` having no native
+/// blocking-with-timeout primitive.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
-    // Block until we can acquire the lock
-    match file
-        .lock_exclusive()
-        .context("failed to acquire lock on config directory")
-    {
-        Ok(_) => Ok(file),
-        Err(err) => Err(err),
+fn try_lock(file: &fs::File, mode: LockMode) -> io::Result<()> {
+    match mode {
+        LockMode::Shared => file.try_lock_shared(),
+        LockMode::Exclusive => file.try_lock_exclusive(),
     }
 }
 
+fn lock_blocking(file: &fs::File, mode: LockMode) -> io::Result<()> {
+    match mode {
+        LockMode::Shared => file.lock_shared(),
+        LockMode::Exclusive => file.lock_exclusive(),
+    }
+}
+
+/// Acquires `mode` on `file`, trying a non-blocking attempt first so an
+/// uncontended lock never prints anything. Only once that fails do we
+/// announce the wait and fall back to a blocking acquire, honoring
+/// `ctx.lock_timeout()` by polling instead of blocking indefinitely so a
+/// stuck lock can't freeze a non-interactive shell init forever.
+fn acquire_on(ctx: &Context, file: &fs::File, mode: LockMode, path: &Path) -> Result<()> {
+    if try_lock(file, mode).is_ok() {
+        return Ok(());
+    }
+
+    ctx.log_verbose_status(
+        "Blocking",
+        &format!("waiting for file lock on `{}`", path.display()),
+    );
+
+    match ctx.lock_timeout() {
+        None => {
+            lock_blocking(file, mode).context("failed to acquire lock on config directory")?;
+        }
+        Some(timeout) => {
+            let start = Instant::now();
+            loop {
+                match try_lock(file, mode) {
+                    Ok(()) => break,
+                    Err(_) if start.elapsed() < timeout => {
+                        thread::sleep(LOCK_POLL_INTERVAL);
+                    }
+                    Err(_) => {
+                        bail!(
+                            "timed out after {}s waiting for file lock on `{}`",
+                            timeout.as_secs(),
+                            path.display()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn acquire_mutex(ctx: &Context, path: &Path, mode: LockMode) -> Result<fs::File> {
+    let file = get_file_for_mutex(path)
+        .map_err(|err| anyhow!("failed to open `{}`: {}", path.display(), err))?;
+    acquire_on(ctx, &file, mode, path)?;
+    Ok(file)
+}
+
+/// Upgrades an already held shared lock on the config directory to an
+/// exclusive one, releasing it first so that the shared/exclusive semantics
+/// are honored on all platforms (including the Windows lock file). Goes
+/// through the same non-blocking-then-timeout-polling path as
+/// `acquire_mutex`, since this is the realistic contended case during
+/// concurrent shell startup that `--lock-timeout` exists to bound.
+fn upgrade_to_exclusive(ctx: &Context, file: &fs::File) -> Result<()> {
+    fs2::FileExt::unlock(file).context("failed to release shared lock on config directory")?;
+    acquire_on(ctx, file, LockMode::Exclusive, ctx.config_dir())
+        .context("failed to acquire exclusive lock on config directory")?;
+    ctx.log_verbose_status("File", "upgraded to exclusive lock on config directory");
+    Ok(())
+}
+
 /// Executes the `init` subcommand.
 ///
 /// Initialize a new config file.
@@ -179,7 +282,7 @@ fn edit(ctx: &Context) -> Result<()> {
             config.to_string()
         }
     };
-    let handle = editor::Editor::default()?.edit(ctx, path, &original_contents)?;
+    let handle = editor::Editor::default(ctx)?.edit(ctx, path, &original_contents)?;
     ctx.log_status("Opened", &"config in temporary file for editing");
     let config = handle.wait_and_update(&original_contents)?;
     config.to_path(path)?;
@@ -248,13 +351,14 @@ fn lock(ctx: &Context, warnings: &mut Vec<Error>) -> Result<()> {
 /// Execute the `source` subcommand.
 ///
 /// Generate and print out the shell script.
-fn source(ctx: &Context, warnings: &mut Vec<Error>) -> Result<()> {
+fn source(ctx: &Context, warnings: &mut Vec<Error>, mutex: &fs::File) -> Result<()> {
     let config_path = ctx.config_file();
     let lock_path = ctx.lock_file();
 
     let mut to_path = true;
 
     let locked_config = if ctx.lock_mode.is_some() || newer_than(config_path, lock_path) {
+        upgrade_to_exclusive(ctx, mutex)?;
         locked(ctx, warnings)?
     } else {
         match lock::from_path(lock_path) {
@@ -262,12 +366,17 @@ fn source(ctx: &Context, warnings: &mut Vec<Error>) -> Result<()> {
                 if locked_config.verify(ctx) {
                     to_path = false;
                     ctx.log_verbose_header("Unlocked", lock_path);
+                    record_last_use(ctx, &locked_config)?;
                     locked_config
                 } else {
+                    upgrade_to_exclusive(ctx, mutex)?;
                     locked(ctx, warnings)?
                 }
             }
-            Err(_) => locked(ctx, warnings)?,
+            Err(_) => {
+                upgrade_to_exclusive(ctx, mutex)?;
+                locked(ctx, warnings)?
+            }
         }
     };
 
@@ -290,6 +399,33 @@ fn source(ctx: &Context, warnings: &mut Vec<Error>) -> Result<()> {
     Ok(())
 }
 
+/// Executes the `gc` subcommand.
+///
+/// Deletes cloned/downloaded plugin sources that are no longer referenced
+/// by the config file and have not been used for at least `keep`. Referenced
+/// is computed across *every* profile declared in the config, not just the
+/// one most recently locked, so that plugins belonging to a profile that
+/// simply isn't active on this machine aren't mistaken for garbage.
+/// Requires the exclusive lock acquired in `run_command`, since collection
+/// mutates the data directory concurrently-running `source` invocations may
+/// be reading from.
+fn gc(ctx: &Context, keep: std::time::Duration) -> Result<()> {
+    let mut warnings = Vec::new();
+    let referenced = match config::from_path(ctx.config_file(), &mut warnings) {
+        Ok(config) => lock::all_source_paths(ctx, &config),
+        Err(_) => Vec::new(),
+    };
+    let summary = gc::gc(ctx, &referenced, keep)?;
+    ctx.log_status(
+        "Collected",
+        &format!(
+            "{} source(s), reclaimed {} bytes",
+            summary.removed, summary.reclaimed_bytes
+        ),
+    );
+    Ok(())
+}
+
 /// Returns `true` if the left path is newer than the right.
 fn newer_than(left: &Path, right: &Path) -> bool {
     let modified = |p| fs::metadata(p).and_then(|m| m.modified()).ok();
@@ -306,5 +442,19 @@ fn locked(ctx: &Context, warnings: &mut Vec<Error>) -> Result<LockedConfig> {
     let config = config::from_path(path, warnings).context("failed to load config file")?;
     ctx.log_header("Loaded", path);
     config::clean(ctx, warnings, &config)?;
-    lock::config(ctx, config)
+    let locked_config = lock::config(ctx, config)?;
+    record_last_use(ctx, &locked_config)?;
+    Ok(locked_config)
+}
+
+/// Records "used now" for every source referenced by `locked_config` in a
+/// single deferred batch write, so a `source`/`lock` invocation touching
+/// many plugins costs one write of the tracker file rather than one per
+/// plugin.
+fn record_last_use(ctx: &Context, locked_config: &LockedConfig) -> Result<()> {
+    let mut tracker = LastUseTracker::load(ctx.data_dir())?;
+    for path in locked_config.source_paths() {
+        tracker.mark_used(&path);
+    }
+    tracker.save()
 }